@@ -1,7 +1,7 @@
 use std::sync::mpsc::Sender;
 use step::FilterType;
 
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -13,9 +13,21 @@ use petgraph::{Directed, Direction};
 
 use failure::{err_msg, Error};
 
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 
-use threadpool::ThreadPool;
+use futures::future::{abortable, AbortHandle, Aborted};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::sync::Semaphore;
+
+/// Upper bound on steps executing concurrently. Unlike the old thread pool this
+/// only gates CPU/connection pressure, so it can be large enough to keep many
+/// lightweight (HTTP/health-check) steps in flight at once.
+const DEFAULT_CONCURRENCY: usize = 1024;
 
 pub struct StepRunner<'a> {
     pub run: RunType,
@@ -24,20 +36,107 @@ pub struct StepRunner<'a> {
     pub filters: Vec<FilterType>,
     pub graph: Arc<GraphMap<usize, Require, Directed>>,
     pub steps: Arc<Mutex<Vec<Status>>>,
-    pub pool: ThreadPool,
+    pub semaphore: Arc<Semaphore>,
     pub name_lookup: Arc<HashMap<&'a str, usize>>,
     pub index: usize,
-    pub notify: Sender<usize>,
+    pub notify: UnboundedSender<usize>,
+    pub cancelled: Arc<AtomicBool>,
+    pub fail_fast: bool,
+    pub timeout: Option<Duration>,
+    pub name: String,
+    pub observer: Option<Sender<ProgressEvent>>,
+    pub cache: Option<Arc<Cache>>,
+    pub keys: Arc<Mutex<Vec<Option<String>>>>,
+    pub recomputed: Arc<Mutex<HashSet<usize>>>,
+}
+
+/// A content-addressed store of step `Outcome`s on disk. Each step's outcome is
+/// keyed by a hash that transitively folds in the keys of its graph inputs, so
+/// a node is only reused when it and everything feeding it are unchanged.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    fn new(dir: PathBuf) -> Result<Cache, Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(Cache { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn get(&self, key: &str) -> Option<Outcome> {
+        let raw = fs::read_to_string(self.path(key)).ok()?;
+        ::serde_json::from_str(&raw).ok()
+    }
+
+    fn put(&self, key: &str, outcome: &Outcome) {
+        if let Ok(raw) = ::serde_json::to_string(outcome) {
+            fs::write(self.path(key), raw).ok();
+        }
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Debug)]
 pub enum Status {
-    InProgress,
+    //Holds the running task's `AbortHandle` so fail-fast can cancel the
+    //in-flight future directly rather than waiting for it to finish.
+    InProgress(AbortHandle),
     Outstanding,
     Completed(Outcome),
 }
 
+/// Lifecycle events emitted while a run is in flight so callers can render a
+/// live dependency-graph dashboard or progress bar keyed by step name.
+#[derive(Clone, Debug)]
+pub enum ProgressEvent {
+    Scheduled { index: usize, name: String },
+    Started { index: usize },
+    Finished { index: usize, outcome: Outcome },
+    Skipped { index: usize, reason: String },
+}
+
 impl<'a> StepRunner<'a> {
+    fn emit(&self, event: ProgressEvent) {
+        if let Some(ref observer) = self.observer {
+            observer.send(event).ok();
+        }
+    }
+
+    //Cache key for this node: the resolved run (which, for `RunType::Step`, has
+    //the upstream step's output already injected, so a stale injected value
+    //forces recomputation), plus its filters/expect and the keys of every
+    //incoming neighbor. Keys therefore depend transitively on inputs.
+    fn cache_key(&self, run: &RunType) -> String {
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", run).hash(&mut hasher);
+        format!("{:?}", self.filters).hash(&mut hasher);
+        format!("{:?}", self.expect).hash(&mut hasher);
+
+        let keys = self.keys.lock().unwrap();
+        for neighbor in self
+            .graph
+            .neighbors_directed(self.index, Direction::Incoming)
+        {
+            if let Some(ref key) = keys[neighbor] {
+                key.hash(&mut hasher);
+            }
+        }
+
+        format!("{:016x}", hasher.finish())
+    }
+
+    //A node is dirty when any of its inputs was recomputed this run, which
+    //forces a miss even on a cache hit and propagates downstream.
+    fn is_dirty(&self) -> bool {
+        let recomputed = self.recomputed.lock().unwrap();
+        self.graph
+            .neighbors_directed(self.index, Direction::Incoming)
+            .any(|neighbor| recomputed.contains(&neighbor))
+    }
+
     pub fn poll(&self) {
         debug!("Poll received for `{}`", self.index);
 
@@ -83,11 +182,37 @@ impl<'a> StepRunner<'a> {
                 error: Some("Dependency Not Met".into()),
                 duration: Duration::from_secs(0),
             });
+            self.emit(ProgressEvent::Skipped {
+                index: self.index,
+                reason: "Dependency Not Met".into(),
+            });
             return;
         }
 
-        if cur_steps[self.index] == Status::Outstanding {
-            cur_steps[self.index] = Status::InProgress;
+        if let Status::Outstanding = cur_steps[self.index] {
+            //If a previous step has already errored in fail-fast mode, don't
+            //bother dispatching this one; mark it cancelled and notify so the
+            //executor still accounts for exactly one completion per step.
+            if self.cancelled.load(Ordering::SeqCst) {
+                cur_steps[self.index] = Status::Completed(Outcome {
+                    output: Some("".into()),
+                    error: Some("Cancelled".into()),
+                    duration: Duration::from_secs(0),
+                });
+                self.emit(ProgressEvent::Skipped {
+                    index: self.index,
+                    reason: "Cancelled".into(),
+                });
+                self.notify
+                    .send(self.index)
+                    .expect("Could not notify executor");
+                return;
+            }
+
+            self.emit(ProgressEvent::Scheduled {
+                index: self.index,
+                name: self.name.clone(),
+            });
 
             let mut run = self.run.clone();
 
@@ -103,18 +228,126 @@ impl<'a> StepRunner<'a> {
                 }
             }
 
+            let index = self.index;
+
+            //Content-addressed caching: compute this node's key now that its run
+            //is resolved and record it so downstream keys can fold it in. The
+            //key is cheap (in-memory hashing); the actual disk lookup is done
+            //inside the task below so it never runs under the status lock.
+            let cache_key = self.cache.as_ref().map(|_| self.cache_key(&run));
+            if let Some(ref key) = cache_key {
+                self.keys.lock().unwrap()[index] = Some(key.clone());
+            }
+            //A node is dirty when one of its inputs was recomputed this run; that
+            //is a fast in-memory check on the recomputed set, so do it here while
+            //everything relevant is already completed, and hand the result to the
+            //task which uses it to decide whether a cache hit may be reused.
+            let dirty = self.cache.is_some() && self.is_dirty();
+
             let expect = self.expect.clone();
             let retry = self.retry;
             let tx = self.notify.clone();
-            let index = self.index;
-            let steps = self.steps.clone();
             let filters = self.filters.clone();
+            let cancelled = self.cancelled.clone();
+            let report_cancelled = self.cancelled.clone();
+            let fail_fast = self.fail_fast;
+            let cache = self.cache.clone();
+            let recomputed = self.recomputed.clone();
+            let steps = self.steps.clone();
+            let observer = self.observer.clone();
+            let semaphore = self.semaphore.clone();
+            let timeout = self.timeout;
+            let task_cache_key = cache_key.clone();
+
+            //The actual work is an abortable future. It first consults the cache
+            //off the status lock (blocking disk reads go to `spawn_blocking` so
+            //they never stall the reactor), and only on a miss does it take a
+            //semaphore permit (bounding in-flight concurrency) and run the step,
+            //with `tokio::time::timeout` acting as the per-step watchdog. Holding
+            //the `AbortHandle` lets fail-fast cancel this future directly, and the
+            //watchdog drops the step's future when it fires — the task itself
+            //still cannot be forcibly unwound mid-`.await`, only dropped, so a
+            //timed-out step reports the timeout budget as its duration and an
+            //aborted one reports zero, rather than the true wall-clock of the
+            //abandoned work.
+            let work = async move {
+                //Clean cache hit: reuse the stored outcome without a permit or
+                //any execution. Dirty nodes always recompute.
+                if !dirty {
+                    if let (Some(cache), Some(key)) = (cache.clone(), task_cache_key.clone()) {
+                        let hit = tokio::task::spawn_blocking(move || cache.get(&key))
+                            .await
+                            .ok()
+                            .flatten();
+                        if let Some(outcome) = hit {
+                            debug!("Cache hit for step `{}`", index);
+                            return outcome;
+                        }
+                    }
+                }
+
+                //Miss (or dirty): this node is about to recompute, so mark it so
+                //everything downstream is treated as dirty too.
+                if cache.is_some() {
+                    recomputed.lock().unwrap().insert(index);
+                }
+
+                let _permit = semaphore
+                    .acquire_owned()
+                    .await
+                    .expect("Semaphore closed unexpectedly");
+                let fut = run.execute(expect, filters, retry, cancelled.clone());
+                let outcome = match timeout {
+                    Some(dur) => match tokio::time::timeout(dur, fut).await {
+                        Ok(outcome) => outcome,
+                        Err(_) => Outcome {
+                            output: Some("".into()),
+                            error: Some("Timed out".into()),
+                            duration: dur,
+                        },
+                    },
+                    None => fut.await,
+                };
+                //Only memoize *successful* outcomes: caching an error or a
+                //timeout would turn a transient failure into a permanent one,
+                //short-circuiting every later run instead of re-running it.
+                if outcome.error.is_none() {
+                    if let (Some(cache), Some(key)) = (cache.as_ref(), task_cache_key.as_ref()) {
+                        cache.put(key, &outcome);
+                    }
+                }
+                outcome
+            };
 
-            //let task = task::current();
-            self.pool.execute(move || {
-                let outcome = run.execute(expect, filters, retry);
+            //Emit `Started` before the abortable boundary so the outer task's
+            //unconditional `Finished` always has a preceding `Started`, even
+            //for a step aborted (fail-fast/timeout) before `work` runs or while
+            //it waits behind the cache lookup or the semaphore permit.
+            self.emit(ProgressEvent::Started { index });
+
+            let (work, abort_handle) = abortable(work);
+            cur_steps[index] = Status::InProgress(abort_handle);
+
+            tokio::spawn(async move {
+                //A cancelled (fail-fast/abort) step resolves to `Err(Aborted)`;
+                //either way we write the slot exactly once and notify exactly
+                //once, preserving the one-completion-per-step invariant.
+                let outcome = match work.await {
+                    Ok(outcome) => outcome,
+                    Err(Aborted) => Outcome {
+                        output: Some("".into()),
+                        error: Some("Cancelled".into()),
+                        duration: Duration::from_secs(0),
+                    },
+                };
                 debug!("Step `{}` done: {:?}", index, outcome);
-                steps.lock().unwrap()[index] = Status::Completed(outcome);
+                if fail_fast && outcome.error.is_some() {
+                    report_cancelled.store(true, Ordering::SeqCst);
+                }
+                steps.lock().unwrap()[index] = Status::Completed(outcome.clone());
+                if let Some(ref observer) = observer {
+                    observer.send(ProgressEvent::Finished { index, outcome }).ok();
+                }
                 tx.send(index).expect("Could not notify executor");
             });
         }
@@ -122,11 +355,54 @@ impl<'a> StepRunner<'a> {
 }
 
 pub fn run_steps(steps: &mut Vec<Step>) -> Result<(), Error> {
+    run_steps_inner(steps, false, None, None)
+}
+
+/// Like [`run_steps`], but when `fail_fast` is true stops the whole run as soon
+/// as any step errors, cancelling in-flight steps. With `fail_fast` false it
+/// behaves exactly like `run_steps`. Fail-fast is opt-in, so existing callers keep
+/// using `run_steps` unchanged.
+pub fn run_steps_fail_fast(steps: &mut Vec<Step>, fail_fast: bool) -> Result<(), Error> {
+    run_steps_inner(steps, fail_fast, None, None)
+}
+
+/// Run the steps, memoizing each outcome on disk under `cache_dir`. A step is
+/// re-executed only when its content-addressed key misses or when one of its
+/// graph inputs was recomputed this run; otherwise the cached outcome is reused
+/// without taking a concurrency permit or executing the step. See [`Cache`] for
+/// the keying scheme.
+pub fn run_steps_cached(steps: &mut Vec<Step>, cache_dir: PathBuf) -> Result<(), Error> {
+    let cache = Arc::new(Cache::new(cache_dir)?);
+    run_steps_inner(steps, false, None, Some(cache))
+}
+
+/// Run the steps while streaming structured lifecycle events to `observer` so
+/// a caller can render live run progress. The returned outcomes are identical
+/// to `run_steps_fail_fast` with the same `fail_fast`; the events simply narrate
+/// the run as it happens.
+pub fn run_steps_with_observer(
+    steps: &mut Vec<Step>,
+    fail_fast: bool,
+    observer: Sender<ProgressEvent>,
+) -> Result<(), Error> {
+    run_steps_inner(steps, fail_fast, Some(observer), None)
+}
+
+fn run_steps_inner(
+    steps: &mut Vec<Step>,
+    fail_fast: bool,
+    observer: Option<Sender<ProgressEvent>>,
+    cache: Option<Arc<Cache>>,
+) -> Result<(), Error> {
     let graph = create_graph(&steps)?;
 
     let steps_status: Arc<Mutex<Vec<Status>>> =
         Arc::new(Mutex::new(vec![Status::Outstanding; steps.len()]));
 
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let keys = Arc::new(Mutex::new(vec![None; steps.len()]));
+    let recomputed = Arc::new(Mutex::new(HashSet::new()));
+
     //We want the runners to drop after this so we can return the steps status
     {
         let mut lookup: HashMap<&str, usize> = HashMap::new();
@@ -141,8 +417,8 @@ pub fn run_steps(steps: &mut Vec<Step>) -> Result<(), Error> {
 
         let mut runners = Vec::new();
 
-        let (tx, rx) = channel();
-        let threadpool = ThreadPool::default();
+        let semaphore = Arc::new(Semaphore::new(DEFAULT_CONCURRENCY));
+        let (tx, mut rx) = unbounded_channel();
 
         for i in 0..steps.len() {
             let future = StepRunner {
@@ -155,36 +431,114 @@ pub fn run_steps(steps: &mut Vec<Step>) -> Result<(), Error> {
                 index: i,
                 name_lookup: name_lookup.clone(),
                 notify: tx.clone(),
-                pool: threadpool.clone(),
+                semaphore: semaphore.clone(),
+                cancelled: cancelled.clone(),
+                fail_fast,
+                timeout: steps[i].timeout,
+                name: steps[i].name.clone(),
+                observer: observer.clone(),
+                cache: cache.clone(),
+                keys: keys.clone(),
+                recomputed: recomputed.clone(),
             };
 
             runners.push(future);
         }
 
-        //Kick off the process
-        for runner in runners.iter_mut() {
-            runner.poll();
-        }
+        //Drop our own handle so the channel closes once every task's sender is
+        //gone; recv then returns `None` and we stop in lock-step with the count.
+        drop(tx);
 
-        for _ in 0..steps.len() {
-            let finished = rx.recv()?;
+        let rt = Runtime::new().map_err(|e| err_msg(format!("Could not start runtime: {}", e)))?;
+        rt.block_on(async {
+            //Kick off the process
+            for runner in runners.iter_mut() {
+                runner.poll();
+            }
+
+            //Each step emits exactly one notification regardless of whether it
+            //ran, was skipped for an unmet dependency, or was cancelled, so we
+            //count completions rather than assuming each poll dispatches work.
+            let mut completed = 0;
+            while completed < steps.len() {
+                let finished = match rx.recv().await {
+                    Some(index) => index,
+                    None => break,
+                };
+                completed += 1;
+
+                //In fail-fast mode an errored step aborts every still-running
+                //sibling's future directly, rather than letting them run out.
+                if fail_fast {
+                    let cur_steps = steps_status.lock().unwrap();
+                    let errored = matches!(
+                        cur_steps[finished],
+                        Status::Completed(ref outcome) if outcome.error.is_some()
+                    );
+                    if errored {
+                        for status in cur_steps.iter() {
+                            if let Status::InProgress(ref handle) = *status {
+                                handle.abort();
+                            }
+                        }
+                    }
+                }
 
-            for neighbor in shared_graph.neighbors_directed(finished, Direction::Outgoing) {
-                runners[neighbor].poll();
+                for neighbor in shared_graph.neighbors_directed(finished, Direction::Outgoing) {
+                    runners[neighbor].poll();
+                }
             }
-        }
+        });
 
-        threadpool.join();
+        //Shut the runtime down before reading results so no task is still
+        //holding the shared status behind our back.
+        drop(rt);
     }
 
-    let steps_ptr =
-        Arc::try_unwrap(steps_status).map_err(|_| err_msg("Could not unwrap arc pointer"))?;
-
-    for (i, status) in steps_ptr.into_inner()?.into_iter().enumerate() {
-        if let Status::Completed(outcome) = status {
-            steps[i].outcome = Some(outcome);
+    let cur_steps = steps_status.lock().unwrap();
+    for (i, status) in cur_steps.iter().enumerate() {
+        if let Status::Completed(ref outcome) = *status {
+            steps[i].outcome = Some(outcome.clone());
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::atomic::AtomicUsize;
+
+    //A fresh, unique cache directory per test. `Date::now`/random aren't used so
+    //a process-scoped counter keeps parallel test runs from colliding.
+    fn temp_cache_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+        env::temp_dir().join(format!("lorikeet-cache-{}-{}", std::process::id(), n))
+    }
+
+    fn outcome(value: &str) -> Outcome {
+        Outcome {
+            output: Some(value.into()),
+            error: None,
+            duration: Duration::from_secs(0),
+        }
+    }
+
+    #[test]
+    fn cache_round_trips_outcomes() {
+        let dir = temp_cache_dir();
+        let cache = Cache::new(dir.clone()).unwrap();
+
+        //Miss before anything is stored, hit with the same value afterwards.
+        assert!(cache.get("abc").is_none());
+        cache.put("abc", &outcome("stored"));
+        assert_eq!(cache.get("abc").unwrap().output, Some("stored".into()));
+        //A different key is still a miss.
+        assert!(cache.get("def").is_none());
+
+        fs::remove_dir_all(dir).ok();
+    }
+}
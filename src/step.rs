@@ -0,0 +1,396 @@
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use regex::Regex;
+use tokio::process::Command;
+
+/// What a step actually does.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunType {
+    /// Run a shell command and capture its stdout.
+    Bash(String),
+    /// Use a literal value as the output.
+    Value(String),
+    /// Reuse the output of another step. The runner replaces the referenced
+    /// step name with that step's resolved output before execution.
+    Step(String),
+}
+
+/// How a step's output is checked for success.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpectType {
+    /// Accept any output.
+    Anything,
+    /// The output must equal this value exactly.
+    Equals(String),
+    /// The output must match this regular expression.
+    Matches(String),
+}
+
+impl Default for ExpectType {
+    fn default() -> ExpectType {
+        ExpectType::Anything
+    }
+}
+
+impl ExpectType {
+    /// Returns `Some(error)` when `output` does not satisfy the expectation.
+    fn check(&self, output: &str) -> Option<String> {
+        match self {
+            ExpectType::Anything => None,
+            ExpectType::Equals(value) => {
+                if output == value {
+                    None
+                } else {
+                    Some(format!("Expected `{}`, got `{}`", value, output))
+                }
+            }
+            ExpectType::Matches(pattern) => match Regex::new(pattern) {
+                Ok(re) if re.is_match(output) => None,
+                Ok(_) => Some(format!("Output did not match `{}`", pattern)),
+                Err(err) => Some(format!("Invalid regex `{}`: {}", pattern, err)),
+            },
+        }
+    }
+}
+
+/// A transformation applied to a step's raw output before it is checked.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterType {
+    /// Keep only the lines matching this regular expression.
+    Matches(String),
+    /// Trim surrounding whitespace.
+    Trim,
+}
+
+fn apply_filters(output: String, filters: &[FilterType]) -> String {
+    let mut result = output;
+    for filter in filters {
+        result = match filter {
+            FilterType::Trim => result.trim().to_string(),
+            FilterType::Matches(pattern) => match Regex::new(pattern) {
+                Ok(re) => result
+                    .lines()
+                    .filter(|line| re.is_match(line))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                Err(_) => result,
+            },
+        };
+    }
+    result
+}
+
+/// The result of running a step: its output, an optional error, and the total
+/// time spent (including any retry backoff sleeps).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Outcome {
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub duration: Duration,
+}
+
+/// How the delay between retries grows.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackoffStrategy {
+    /// Wait the same `base` delay before every retry.
+    Fixed,
+    /// Grow the delay geometrically as `base * factor^n`, using the
+    /// [`RetryPolicy::factor`] field.
+    Exponential,
+}
+
+impl Default for BackoffStrategy {
+    fn default() -> BackoffStrategy {
+        BackoffStrategy::Fixed
+    }
+}
+
+/// Controls how many times and how patiently a failing step is retried.
+///
+/// The fields map directly onto the flat step config, e.g.
+/// `retry: { count: 5, backoff: exponential, base: 200ms, max: 10s, factor: 2.0, jitter: 0.2 }`.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// How many times to retry after the first attempt fails.
+    pub count: usize,
+    /// The growth strategy for the delay between retries.
+    #[serde(rename = "backoff")]
+    pub strategy: BackoffStrategy,
+    /// The base delay before the first retry. Parsed from a human-friendly
+    /// duration such as `200ms` or `2s`.
+    #[serde(with = "humantime_serde")]
+    pub base: Duration,
+    /// The cap on any single delay, e.g. `10s`. A zero cap means "no cap".
+    #[serde(with = "humantime_serde")]
+    pub max: Duration,
+    /// The multiplier for `Exponential` backoff. Ignored for `Fixed`.
+    pub factor: f64,
+    /// Optional jitter fraction `j`: each delay is multiplied by a uniform
+    /// random factor in `[1 - j, 1 + j]` to avoid synchronised retries.
+    pub jitter: Option<f64>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        // `Fixed` with a zero base reproduces the original behaviour: retries
+        // happen back-to-back with no delay, so existing playbooks are
+        // unaffected by the richer policy.
+        RetryPolicy {
+            count: 0,
+            strategy: BackoffStrategy::Fixed,
+            base: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+            factor: 2.0,
+            jitter: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay to wait before retry attempt `n` (1-based). `Exponential`
+    /// grows as `base * factor^n`, so the first retry already waits
+    /// `base * factor`; the result is capped at `max` (when non-zero) and, if
+    /// `jitter` is set, perturbed by a uniform factor in `[1 - j, 1 + j]`.
+    fn backoff(&self, n: u32) -> Duration {
+        let base = self.base.as_secs_f64();
+        let raw = match self.strategy {
+            BackoffStrategy::Fixed => base,
+            BackoffStrategy::Exponential => base * self.factor.powi(n as i32),
+        };
+
+        // Clamp in the f64 domain *before* constructing the `Duration`. An
+        // uncapped exponential product can overflow f64→`Duration`, and
+        // `Duration::from_secs_f64` panics on an overflowing/non-finite value,
+        // so a configured `max` has to be applied here rather than after —
+        // otherwise a sane cap wouldn't protect the user from the panic.
+        let mut secs = if self.max.is_zero() {
+            // No cap: saturate an overflowing product instead of panicking.
+            if raw.is_finite() && raw < u64::MAX as f64 {
+                raw
+            } else {
+                return Duration::new(u64::MAX, 0);
+            }
+        } else {
+            raw.min(self.max.as_secs_f64())
+        };
+
+        if let Some(jitter) = self.jitter {
+            let jitter = jitter.max(0.0);
+            // The single-range `gen_range(low..=high)` form requires rand >= 0.8.
+            let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+            secs *= factor.max(0.0);
+        }
+
+        Duration::from_secs_f64(secs.max(0.0))
+    }
+}
+
+impl RunType {
+    /// Run the step, retrying on failure per `retry`. Returns once the step
+    /// succeeds, the retry budget is exhausted, or cancellation is observed.
+    /// The returned `duration` covers every attempt plus the backoff sleeps
+    /// between them.
+    pub async fn execute(
+        self,
+        expect: ExpectType,
+        filters: Vec<FilterType>,
+        retry: RetryPolicy,
+        cancelled: Arc<AtomicBool>,
+    ) -> Outcome {
+        let start = Instant::now();
+        let mut attempt: u32 = 0;
+
+        loop {
+            // Bail out between attempts if a fail-fast cancellation has fired,
+            // so a retrying step doesn't keep hammering a service after the run
+            // has already been abandoned.
+            if cancelled.load(Ordering::SeqCst) {
+                return Outcome {
+                    output: None,
+                    error: Some("Cancelled".into()),
+                    duration: start.elapsed(),
+                };
+            }
+
+            let mut outcome = self.attempt(&expect, &filters).await;
+
+            if outcome.error.is_none() || attempt >= retry.count as u32 {
+                outcome.duration = start.elapsed();
+                return outcome;
+            }
+
+            attempt += 1;
+            let delay = retry.backoff(attempt);
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    /// A single attempt: produce the raw output, apply filters, and check it
+    /// against the expectation.
+    async fn attempt(&self, expect: &ExpectType, filters: &[FilterType]) -> Outcome {
+        let started = Instant::now();
+
+        let raw = match self {
+            RunType::Bash(command) => match run_command(command).await {
+                Ok(output) => output,
+                Err(err) => {
+                    return Outcome {
+                        output: None,
+                        error: Some(err),
+                        duration: started.elapsed(),
+                    };
+                }
+            },
+            RunType::Value(value) | RunType::Step(value) => value.clone(),
+        };
+
+        let output = apply_filters(raw, filters);
+        let error = expect.check(&output);
+
+        Outcome {
+            output: Some(output),
+            error,
+            duration: started.elapsed(),
+        }
+    }
+}
+
+async fn run_command(command: &str) -> Result<String, String> {
+    // `kill_on_drop` is what makes the per-step timeout actually terminate a
+    // wedged child: when the watchdog times the step out it drops this future,
+    // which drops the `Child` and sends it a kill, so the process can't linger
+    // past its step. Without this the dropped future would only abandon, not
+    // kill, the child.
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+/// A single node in a playbook.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Step {
+    pub name: String,
+    pub run: RunType,
+    #[serde(default)]
+    pub expect: ExpectType,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub filters: Vec<FilterType>,
+    /// Abandon the step if it has not finished within this budget, e.g.
+    /// `timeout: 30s`.
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    /// Populated by the runner once the step has been executed.
+    #[serde(skip)]
+    pub outcome: Option<Outcome>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exponential(base: Duration, factor: f64, max: Duration) -> RetryPolicy {
+        RetryPolicy {
+            count: 10,
+            strategy: BackoffStrategy::Exponential,
+            base,
+            max,
+            factor,
+            jitter: None,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        // With jitter disabled the delay is deterministic: base * factor^n.
+        let policy = exponential(Duration::from_millis(100), 2.0, Duration::from_secs(0));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn backoff_respects_max_cap() {
+        let policy = exponential(Duration::from_millis(100), 10.0, Duration::from_secs(1));
+        // 100ms * 10^2 = 10s, capped back down to the 1s max.
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_caps_without_overflowing() {
+        // base * factor^n overflows a `Duration` long before n reaches 100;
+        // the `max` cap must be applied in the f64 domain so this does not
+        // panic in `Duration::from_secs_f64`.
+        let policy = exponential(Duration::from_millis(200), 2.0, Duration::from_secs(10));
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn exponential_variant_deserializes_from_bare_tag() {
+        // The documented flat config — `backoff: exponential`, `factor`
+        // alongside it — must round-trip rather than requiring a nested map.
+        let policy: RetryPolicy =
+            ::serde_json::from_str(r#"{"backoff":"exponential","factor":3.0}"#).unwrap();
+        assert!(matches!(policy.strategy, BackoffStrategy::Exponential));
+        assert_eq!(policy.factor, 3.0);
+    }
+
+    #[test]
+    fn backoff_fixed_is_constant() {
+        let policy = RetryPolicy {
+            count: 3,
+            strategy: BackoffStrategy::Fixed,
+            base: Duration::from_millis(50),
+            max: Duration::from_secs(0),
+            factor: 2.0,
+            jitter: None,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(50));
+        assert_eq!(policy.backoff(5), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn expect_check() {
+        assert_eq!(ExpectType::Anything.check("anything"), None);
+        assert_eq!(ExpectType::Equals("ok".into()).check("ok"), None);
+        assert!(ExpectType::Equals("ok".into()).check("no").is_some());
+        assert_eq!(ExpectType::Matches("^a.*z$".into()).check("abcz"), None);
+        assert!(ExpectType::Matches("^a.*z$".into()).check("nope").is_some());
+        // An invalid pattern is surfaced as an error rather than a match.
+        assert!(ExpectType::Matches("(".into()).check("x").is_some());
+    }
+
+    #[test]
+    fn filters_are_applied_in_order() {
+        let filters = vec![
+            FilterType::Matches("keep".into()),
+            FilterType::Trim,
+        ];
+        let output = "  drop\nkeep me\ndrop\n".to_string();
+        assert_eq!(apply_filters(output, &filters), "keep me");
+    }
+}
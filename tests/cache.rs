@@ -0,0 +1,57 @@
+//! Content-addressed caching must propagate dirtiness: when an upstream step
+//! changes (and therefore misses the cache), a downstream step that injects the
+//! upstream's output has to recompute too, rather than reusing a stale cached
+//! outcome built from the old value.
+
+extern crate lorikeet;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lorikeet::runner::run_steps_cached;
+use lorikeet::step::{ExpectType, RetryPolicy, RunType, Step};
+
+fn step(name: &str, run: RunType) -> Step {
+    Step {
+        name: name.to_string(),
+        run,
+        expect: ExpectType::Anything,
+        retry: RetryPolicy::default(),
+        filters: Vec::new(),
+        timeout: None,
+        outcome: None,
+    }
+}
+
+fn temp_cache_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::SeqCst);
+    env::temp_dir().join(format!("lorikeet-cache-it-{}-{}", std::process::id(), n))
+}
+
+#[test]
+fn changed_upstream_forces_downstream_recompute() {
+    let dir = temp_cache_dir();
+
+    // First run populates the cache: `down` injects `up`'s output ("A").
+    let mut steps = vec![
+        step("up", RunType::Value("A".into())),
+        step("down", RunType::Step("up".into())),
+    ];
+    run_steps_cached(&mut steps, dir.clone()).expect("first run");
+    assert_eq!(steps[1].outcome.as_ref().unwrap().output, Some("A".into()));
+
+    // Second run against the same cache, but `up` now resolves to "B". `up`
+    // misses its key and recomputes, which must dirty `down` so it reflects the
+    // new upstream value rather than the cached "A".
+    let mut steps = vec![
+        step("up", RunType::Value("B".into())),
+        step("down", RunType::Step("up".into())),
+    ];
+    run_steps_cached(&mut steps, dir.clone()).expect("second run");
+    assert_eq!(steps[1].outcome.as_ref().unwrap().output, Some("B".into()));
+
+    fs::remove_dir_all(dir).ok();
+}
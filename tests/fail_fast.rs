@@ -0,0 +1,43 @@
+//! Fail-fast runs must still account for every step. The recv loop in
+//! `run_steps_inner` expects exactly `steps.len()` notifications, so a step
+//! that is cancelled or skipped because an upstream dependency errored must
+//! still emit one completion. The observable consequence is that `run_steps`
+//! returns and *every* step ends up with an `outcome`, even the ones that
+//! never ran — if any notification were dropped the loop would block forever.
+
+extern crate lorikeet;
+
+use lorikeet::runner::run_steps_fail_fast;
+use lorikeet::step::{ExpectType, RetryPolicy, RunType, Step};
+
+fn step(name: &str, run: RunType) -> Step {
+    Step {
+        name: name.to_string(),
+        run,
+        expect: ExpectType::Anything,
+        retry: RetryPolicy::default(),
+        filters: Vec::new(),
+        timeout: None,
+        outcome: None,
+    }
+}
+
+#[test]
+fn every_step_gets_an_outcome_when_a_mid_graph_step_errors() {
+    // `boom` errors; `after` depends on it and must be skipped; `other` is an
+    // independent leaf. All three must come back with an outcome.
+    let mut steps = vec![
+        step("boom", RunType::Bash("exit 1".into())),
+        step("after", RunType::Step("boom".into())),
+        step("other", RunType::Value("fine".into())),
+    ];
+
+    run_steps_fail_fast(&mut steps, true).expect("run should complete");
+
+    assert!(
+        steps.iter().all(|s| s.outcome.is_some()),
+        "fail-fast must notify once per step so the run terminates"
+    );
+    assert!(steps[0].outcome.as_ref().unwrap().error.is_some());
+    assert!(steps[1].outcome.as_ref().unwrap().error.is_some());
+}
@@ -0,0 +1,64 @@
+//! The streaming observer must emit a well-formed lifecycle per step: a
+//! dispatched step goes `Scheduled -> Started -> Finished`, and a step whose
+//! dependency failed is reported once as `Skipped` (never a bare `Finished`).
+
+extern crate lorikeet;
+
+use std::sync::mpsc::channel;
+
+use lorikeet::runner::{run_steps_with_observer, ProgressEvent};
+use lorikeet::step::{ExpectType, RetryPolicy, RunType, Step};
+
+fn step(name: &str, run: RunType) -> Step {
+    Step {
+        name: name.to_string(),
+        run,
+        expect: ExpectType::Anything,
+        retry: RetryPolicy::default(),
+        filters: Vec::new(),
+        timeout: None,
+        outcome: None,
+    }
+}
+
+/// The events for a single step index, in emission order.
+fn events_for(events: &[ProgressEvent], want: usize) -> Vec<&'static str> {
+    events
+        .iter()
+        .filter_map(|event| match *event {
+            ProgressEvent::Scheduled { index, .. } if index == want => Some("scheduled"),
+            ProgressEvent::Started { index } if index == want => Some("started"),
+            ProgressEvent::Finished { index, .. } if index == want => Some("finished"),
+            ProgressEvent::Skipped { index, .. } if index == want => Some("skipped"),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn dispatched_step_reports_scheduled_started_finished() {
+    let mut steps = vec![step("only", RunType::Value("hi".into()))];
+
+    let (tx, rx) = channel();
+    run_steps_with_observer(&mut steps, false, tx).expect("run should complete");
+    let events: Vec<_> = rx.iter().collect();
+
+    assert_eq!(events_for(&events, 0), ["scheduled", "started", "finished"]);
+}
+
+#[test]
+fn skipped_step_reports_only_skipped() {
+    // `boom` errors; `after` depends on it and must be reported as Skipped
+    // with no Started/Finished of its own.
+    let mut steps = vec![
+        step("boom", RunType::Bash("exit 1".into())),
+        step("after", RunType::Step("boom".into())),
+    ];
+
+    let (tx, rx) = channel();
+    run_steps_with_observer(&mut steps, false, tx).expect("run should complete");
+    let events: Vec<_> = rx.iter().collect();
+
+    assert_eq!(events_for(&events, 0), ["scheduled", "started", "finished"]);
+    assert_eq!(events_for(&events, 1), ["skipped"]);
+}